@@ -0,0 +1,327 @@
+//! runtime-editable configuration: which engines exist, and which ssid maps to which
+//! default engine. Loaded from a TOML file so users can add engines/rules without recompiling.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::search_engine::{DuckDuckGoEngine, EngineHandle, GenericEngine, GoogleEngine, WikipediaEngine};
+
+/// on-disk representation of a single engine entry
+#[derive(Deserialize)]
+struct EngineEntry {
+    /// selects which `Engine` implementation (and therefore suggestion/result parsing) to
+    /// use; defaults to a generic engine (duckduckgo-style suggestion parsing, no result
+    /// scraping) if omitted or unrecognized
+    #[serde(default)]
+    kind: String,
+    search_url: String,
+    suggest_url: String,
+}
+
+/// construct the right `Engine` impl for an entry's `kind`
+fn build_engine(key: &str, entry: EngineEntry) -> EngineHandle {
+    let name = key.to_string();
+    match entry.kind.as_str() {
+        "google" => Arc::new(GoogleEngine { name, search_url: entry.search_url, suggest_url: entry.suggest_url }),
+        "duckduckgo" => Arc::new(DuckDuckGoEngine { name, search_url: entry.search_url, suggest_url: entry.suggest_url }),
+        "wikipedia" => Arc::new(WikipediaEngine { name, search_url: entry.search_url, suggest_url: entry.suggest_url }),
+        _ => Arc::new(GenericEngine { name, search_url: entry.search_url, suggest_url: entry.suggest_url }),
+    }
+}
+
+/// on-disk representation of the outbound http client settings used for every upstream
+/// engine request (result scraping and server-side suggestions)
+#[derive(Deserialize, Default)]
+struct HttpClientEntry {
+    /// optional outbound proxy url (http, https, or socks5) to route upstream requests
+    /// through, e.g. for privacy
+    proxy: Option<String>,
+    /// max idle connections kept open per host in the pool
+    pool_max_idle_per_host: Option<usize>,
+    /// tcp keepalive interval, in seconds
+    tcp_keepalive_secs: Option<u64>,
+    /// per-request timeout, in seconds
+    timeout_secs: Option<u64>,
+}
+
+/// resolved http client settings, with defaults filled in
+#[derive(Clone)]
+pub struct HttpClientSettings {
+    pub proxy: Option<String>,
+    pub pool_max_idle_per_host: usize,
+    pub tcp_keepalive: Option<Duration>,
+    pub timeout: Duration,
+}
+
+impl From<HttpClientEntry> for HttpClientSettings {
+    fn from(entry: HttpClientEntry) -> Self {
+        HttpClientSettings {
+            proxy: entry.proxy,
+            pool_max_idle_per_host: entry.pool_max_idle_per_host.unwrap_or(10),
+            tcp_keepalive: entry.tcp_keepalive_secs.map(Duration::from_secs),
+            timeout: Duration::from_secs(entry.timeout_secs.unwrap_or(10)),
+        }
+    }
+}
+
+/// build the shared `reqwest::Client` used for all outgoing engine requests, so that
+/// connections get reused across `/search` and `/suggest` calls instead of reconnecting
+/// on every request
+pub fn build_client(settings: &HttpClientSettings) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder()
+        .pool_max_idle_per_host(settings.pool_max_idle_per_host)
+        .timeout(settings.timeout);
+
+    if let Some(keepalive) = settings.tcp_keepalive {
+        builder = builder.tcp_keepalive(keepalive);
+    }
+
+    if let Some(proxy_url) = &settings.proxy {
+        let proxy = reqwest::Proxy::all(proxy_url).expect("invalid http_client.proxy url in config");
+        builder = builder.proxy(proxy);
+    }
+
+    builder.build().expect("failed to build outbound http client")
+}
+
+/// on-disk representation of the per-ip rate limit applied to `/search` and `/suggest`
+#[derive(Deserialize, Default)]
+struct RateLimitEntry {
+    /// rate limiting is opt-in; off unless explicitly enabled
+    enabled: Option<bool>,
+    /// length of the sliding window, in seconds
+    window_secs: Option<u64>,
+    /// max requests a single client ip may make within the window
+    max_requests: Option<u32>,
+}
+
+/// resolved rate limit settings; `None` means rate limiting is disabled
+#[derive(Clone)]
+pub struct RateLimitSettings {
+    pub window: Duration,
+    pub max_requests: u32,
+}
+
+impl RateLimitEntry {
+    fn resolve(self) -> Option<RateLimitSettings> {
+        if !self.enabled.unwrap_or(false) {
+            return None;
+        }
+        Some(RateLimitSettings {
+            window: Duration::from_secs(self.window_secs.unwrap_or(60)),
+            max_requests: self.max_requests.unwrap_or(60),
+        })
+    }
+}
+
+/// on-disk representation of suggestion-aggregation settings for `/suggest?aggregate=true`
+#[derive(Deserialize, Default)]
+struct SuggestEntry {
+    /// max number of merged suggestions returned by an aggregated `/suggest` request
+    max_aggregate_results: Option<usize>,
+}
+
+/// on-disk representation of an ssid-based default engine rule
+#[derive(Deserialize)]
+struct SsidRuleEntry {
+    /// match when the connected ssid contains this substring
+    contains: String,
+    /// bang key of the engine to use as default when matched
+    engine: String,
+}
+
+#[derive(Deserialize)]
+struct ConfigFile {
+    /// bang key of the engine used when no ssid rule matches (or there's no wifi info at all)
+    default_engine: String,
+    /// bang key of the engine preferred for bang-suggestions; banged engines often handle
+    /// suggestions for bangs poorly, so we fall back to a known-good suggester instead
+    bang_suggester: Option<String>,
+    #[serde(default)]
+    ssid_rules: Vec<SsidRuleEntry>,
+    #[serde(default)]
+    http_client: HttpClientEntry,
+    #[serde(default)]
+    rate_limit: RateLimitEntry,
+    #[serde(default)]
+    suggest: SuggestEntry,
+    engines: HashMap<String, EngineEntry>,
+}
+
+/// fully loaded, validated configuration ready for use by the search routes. Cheap-ish to
+/// clone (engines are `Arc`s) so routes can pull an owned snapshot out of the `RwLock`
+/// before doing any `.await`, rather than holding the read guard for the duration of an
+/// outbound request.
+#[derive(Clone)]
+pub struct Config {
+    pub engines: HashMap<String, EngineHandle>,
+    pub default_engine: String,
+    pub bang_suggester: Option<String>,
+    /// (ssid substring, engine key), checked in file order
+    pub ssid_rules: Vec<(String, String)>,
+    pub http_client: HttpClientSettings,
+    pub rate_limit: Option<RateLimitSettings>,
+    /// max number of merged suggestions returned by an aggregated `/suggest` request
+    pub suggest_max_results: usize,
+}
+
+#[derive(Debug)]
+pub struct ConfigError(pub String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "config error: {}", self.0)
+    }
+}
+
+impl Config {
+    fn load(path: &str) -> Result<Config, ConfigError> {
+        let raw = fs::read_to_string(path).map_err(|e| ConfigError(format!("reading {}: {}", path, e)))?;
+        let parsed: ConfigFile = toml::from_str(&raw).map_err(|e| ConfigError(format!("parsing {}: {}", path, e)))?;
+
+        let engines: HashMap<String, EngineHandle> = parsed.engines.into_iter().map(|(key, entry)| {
+            let engine = build_engine(&key, entry);
+            (key, engine)
+        }).collect();
+
+        if !engines.contains_key(&parsed.default_engine) {
+            return Err(ConfigError(format!("default_engine '{}' is not a declared engine", parsed.default_engine)));
+        }
+        if let Some(bang) = &parsed.bang_suggester {
+            if !engines.contains_key(bang) {
+                return Err(ConfigError(format!("bang_suggester '{}' is not a declared engine", bang)));
+            }
+        }
+        for rule in &parsed.ssid_rules {
+            if !engines.contains_key(&rule.engine) {
+                return Err(ConfigError(format!("ssid rule for '{}' references unknown engine '{}'", rule.contains, rule.engine)));
+            }
+        }
+
+        Ok(Config {
+            engines,
+            default_engine: parsed.default_engine,
+            bang_suggester: parsed.bang_suggester,
+            ssid_rules: parsed.ssid_rules.into_iter().map(|r| (r.contains, r.engine)).collect(),
+            http_client: parsed.http_client.into(),
+            rate_limit: parsed.rate_limit.resolve(),
+            suggest_max_results: parsed.suggest.max_aggregate_results.unwrap_or(10),
+        })
+    }
+}
+
+#[cfg(test)]
+mod load_tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    /// write `contents` to a uniquely-named file under the system temp dir and return its
+    /// path; the file is left in place, cleaned up by the OS's normal temp-dir rules
+    fn write_temp_config(contents: &str) -> String {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+        let path = std::env::temp_dir().join(format!("dynamic_search_test_{}.toml", nanos));
+        fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn rejects_unknown_default_engine() {
+        let path = write_temp_config(r#"
+            default_engine = "nope"
+
+            [engines.ddg]
+            search_url = "https://duckduckgo.com/?q={searchTerms}"
+            suggest_url = "https://duckduckgo.com/ac/?q={searchTerms}&type=list"
+        "#);
+
+        let err = Config::load(&path).err().unwrap();
+        assert!(err.0.contains("default_engine"));
+    }
+
+    #[test]
+    fn rejects_unknown_bang_suggester() {
+        let path = write_temp_config(r#"
+            default_engine = "ddg"
+            bang_suggester = "nope"
+
+            [engines.ddg]
+            search_url = "https://duckduckgo.com/?q={searchTerms}"
+            suggest_url = "https://duckduckgo.com/ac/?q={searchTerms}&type=list"
+        "#);
+
+        let err = Config::load(&path).err().unwrap();
+        assert!(err.0.contains("bang_suggester"));
+    }
+
+    #[test]
+    fn rejects_ssid_rule_with_unknown_engine() {
+        let path = write_temp_config(r#"
+            default_engine = "ddg"
+
+            [[ssid_rules]]
+            contains = "BVSD"
+            engine = "nope"
+
+            [engines.ddg]
+            search_url = "https://duckduckgo.com/?q={searchTerms}"
+            suggest_url = "https://duckduckgo.com/ac/?q={searchTerms}&type=list"
+        "#);
+
+        let err = Config::load(&path).err().unwrap();
+        assert!(err.0.contains("ssid rule"));
+    }
+
+    #[test]
+    fn loads_a_valid_config() {
+        let path = write_temp_config(r#"
+            default_engine = "ddg"
+
+            [engines.ddg]
+            kind = "duckduckgo"
+            search_url = "https://duckduckgo.com/?q={searchTerms}"
+            suggest_url = "https://duckduckgo.com/ac/?q={searchTerms}&type=list"
+        "#);
+
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.default_engine, "ddg");
+        assert!(config.engines.contains_key("ddg"));
+    }
+}
+
+/// shared, reloadable handle to the current config. Cloning shares the same underlying
+/// config (and path), so it can be handed both to Rocket's managed state and to a
+/// background reload task.
+#[derive(Clone)]
+pub struct ConfigState {
+    path: Arc<String>,
+    current: Arc<RwLock<Config>>,
+}
+
+impl ConfigState {
+    /// load the config file at `path` for the first time
+    pub fn load(path: &str) -> Result<ConfigState, ConfigError> {
+        let current = Config::load(path)?;
+        Ok(ConfigState {
+            path: Arc::new(path.to_string()),
+            current: Arc::new(RwLock::new(current)),
+        })
+    }
+
+    /// borrow the currently loaded config
+    pub fn get(&self) -> RwLockReadGuard<'_, Config> {
+        self.current.read().unwrap()
+    }
+
+    /// re-read the config file from disk, replacing the in-memory config on success.
+    /// on failure the previously loaded config is left in place.
+    pub fn reload(&self) -> Result<(), ConfigError> {
+        let fresh = Config::load(&self.path)?;
+        *self.current.write().unwrap() = fresh;
+        Ok(())
+    }
+}
@@ -1,146 +1,109 @@
 #[macro_use] extern crate rocket;
-#[macro_use] extern crate lazy_static;
-use rocket::response::Redirect;
+
+mod config;
+mod rate_limit;
+mod search_engine;
+
 use rocket::fs::NamedFile;
-use urlencoding::encode;
+use rocket::response::content::RawHtml;
+use rocket::response::Redirect;
+use rocket::serde::json::Json;
+use rocket::State;
 use std::path::Path;
-use std::collections::HashMap;
-use std::process::Command;
-
-/// A search engine that we can redirect to
-#[derive(PartialEq, Eq, Clone, Copy)]
-pub struct SearchEngine<'a> {
-    search_url: &'a str,
-    suggest_url: &'a str,
-}
 
-// search engine declarations
-const GOOGLE: SearchEngine = SearchEngine {
-    search_url: "https://www.google.com/search?hl=en&q={searchTerms}",
-    suggest_url: "https://www.google.com/complete/search?hl=en&client=firefox&q={searchTerms}"
-};
-
-const DEFAULT_SUGGEST: &'static str = "https://duckduckgo.com/ac/?q={searchTerms}&type=list";
-
-const DUCKDUCKGO: SearchEngine = SearchEngine {
-    search_url: "https://duckduckgo.com/?q={searchTerms}",
-    suggest_url: "https://duckduckgo.com/ac/?q={searchTerms}&type=list"
-};
-
-const WIKIPEDIA: SearchEngine = SearchEngine {
-    search_url: "https://en.wikipedia.org/w/index.php?title=Special:Search&search={searchTerms}",
-    suggest_url: "https://en.wikipedia.org/w/api.php?action=opensearch&search={searchTerms}&namespace=0"
-};
-
-const NWS: SearchEngine = SearchEngine {
-    search_url: "https://forecast.weather.gov/zipcity.php?inputstring={searchTerms}",
-    suggest_url: DEFAULT_SUGGEST
-};
-
-const CPP: SearchEngine = SearchEngine {
-    search_url: "https://en.cppreference.com/mwiki/index.php?search={searchTerms}",
-    suggest_url: DEFAULT_SUGGEST
-};
-
-const RUST: SearchEngine = SearchEngine {
-    search_url: "https://doc.rust-lang.org/std/?search={searchTerms}",
-    suggest_url: DEFAULT_SUGGEST
-};
-
-const CRATES: SearchEngine = SearchEngine {
-    search_url: "https://crates.io/search?q={searchTerms}",
-    suggest_url: DEFAULT_SUGGEST
-};
-
-lazy_static!{
-    static ref SEARCH_ENGINES: HashMap<&'static str, SearchEngine<'static>> = [
-        ("g", GOOGLE),
-        ("ddg", DUCKDUCKGO),
-        ("w", WIKIPEDIA),
-        ("nws", NWS),
-        ("cpp", CPP),
-        ("rust", RUST),
-        ("crates", CRATES)
-    ].iter().copied().collect();
-}
+use config::ConfigState;
+use rate_limit::{RateLimited, RateLimiter};
+use search_engine::{aggregate_search, aggregate_suggestions, get_bang_suggester, get_engine, SearchResult};
 
-/// get the ssid we're connected to
-fn get_ssid() -> Option<String> {
-    Some(String::from_utf8(Command::new("iwgetid").arg("-r").output().ok()?.stdout).ok()?)
+/// path to the engine/ssid config file, overridable so deployments don't have to run from
+/// a fixed working directory
+fn config_path() -> String {
+    std::env::var("DYNAMIC_SEARCH_CONFIG").unwrap_or_else(|_| "config.toml".to_string())
 }
 
-/// get prefered search based on ssid
-fn base_engine() -> SearchEngine<'static> {
-    let ssid = get_ssid();
-    match ssid {
-        None => DUCKDUCKGO,
-        Some(ssid) => {
-            if ssid.contains("BVSD") {
-                GOOGLE
-            } else {
-                DUCKDUCKGO
-            }
-        }
-    }
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
 }
 
-/// return the prefered bang suggester (if available)
-/// banged engines don't handle bangs in suggestions well, so use this instead
-fn get_bang_suggester() -> Option<SearchEngine<'static>> {
-    Some(DUCKDUCKGO)
-}
+/// render the merged results and any per-engine errors as a standalone results page
+fn render_aggregate_page(q: &str, results: Vec<SearchResult>, errors: Vec<(String, String)>) -> String {
+    let mut body = String::new();
+    body.push_str(&format!("<!DOCTYPE html><html><head><title>{} - search</title></head><body>", html_escape(q)));
 
-/// select a search engine for use
-fn get_engine(query: &str) -> (SearchEngine<'static>, &str) {
-    // check for bang command
-    if query.len() >= 1 && query.chars().nth(0) == Some('!') {
-        // get name before space
-        let mut index = 0;
-        for c in query.chars() {
-            index += 1;
-            if c.is_whitespace() {
-                break
-            }
-        }
-        let bang = &query[1..index-1];
-        // lookup bang
-        match SEARCH_ENGINES.get(bang) {
-            Some(engine) => {
-                if bang.len() + 2 <= query.len() {
-                    return (*engine, &query[bang.len() + 2..])
-                }
-            }
-            None => {}
-        }
+    for (engine, err) in &errors {
+        body.push_str(&format!(
+            "<p class=\"engine-error\">{} failed: {}</p>",
+            html_escape(engine), html_escape(err)
+        ));
+    }
+
+    for result in &results {
+        body.push_str(&format!(
+            "<div class=\"result\"><a href=\"{url}\"><h3>{title}</h3></a><p>{snippet}</p><p class=\"from\">from: {engines}</p></div>",
+            url = html_escape(&result.url),
+            title = html_escape(&result.title),
+            snippet = html_escape(&result.snippet),
+            engines = html_escape(&result.engines.join(", ")),
+        ));
     }
 
-    (base_engine(), query)
+    body.push_str("</body></html>");
+    body
 }
 
-fn format_url(q: &str, format: &str) -> String {
-    format.replace("{searchTerms}", &encode(q).into_owned())
+/// either a redirect to a single engine, or a rendered page of merged results
+#[derive(Responder)]
+#[allow(clippy::large_enum_variant)]
+enum SearchResponse {
+    Redirect(Redirect),
+    Aggregated(RawHtml<String>),
 }
 
 // search endpoint
-#[get("/search?<q>")]
-fn search(q: &str) -> Redirect {
-    let (engine, new_query) = get_engine(q);
-    Redirect::to(format_url(new_query, engine.search_url))
+#[get("/search?<q>&<aggregate>")]
+async fn search(q: &str, aggregate: Option<bool>, config: &State<ConfigState>, client: &State<reqwest::Client>, _limit: RateLimited) -> SearchResponse {
+    if aggregate.unwrap_or(false) {
+        // snapshot the config and drop the read guard before awaiting, so an in-flight
+        // aggregate request doesn't hold the lock (and block /reload) for the full
+        // outbound-request timeout
+        let cfg = config.get().clone();
+        let (results, errors) = aggregate_search(q, &cfg, client).await;
+        return SearchResponse::Aggregated(RawHtml(render_aggregate_page(q, results, errors)));
+    }
+
+    let (engine, new_query) = get_engine(q, &config.get());
+    SearchResponse::Redirect(Redirect::to(engine.search_url(new_query)))
 }
 
-// search suggestion endpoint
-#[get("/suggest?<q>")]
-fn suggest(q: &str) -> Redirect {
-    let (mut engine, new_query) = get_engine(q);
+/// search suggestion endpoint. Fetches the engine's own suggest response server-side and
+/// normalizes it to the common OpenSearch `[query, [suggestion, ...]]` shape, since each
+/// provider's response format differs and browsers only understand the OpenSearch one.
+/// With `aggregate=true`, suggestions from several engines are merged instead of just one.
+#[get("/suggest?<q>&<aggregate>")]
+async fn suggest(q: &str, aggregate: Option<bool>, config: &State<ConfigState>, client: &State<reqwest::Client>, _limit: RateLimited) -> Json<(String, Vec<String>)> {
+    if aggregate.unwrap_or(false) {
+        // same reasoning as the aggregate branch of `search`: snapshot and drop the read
+        // guard before awaiting any outbound requests
+        let cfg = config.get().clone();
+        let suggestions = aggregate_suggestions(q, &cfg, client, cfg.suggest_max_results).await;
+        return Json((q.to_string(), suggestions));
+    }
+
+    let (mut engine, new_query) = get_engine(q, &config.get());
     // if this search is a bang, use the bang suggester if available
     if new_query != q {
-        engine = match get_bang_suggester() {
-            Some(e) => e,
-            None => engine
-        };
+        engine = get_bang_suggester(&config.get()).unwrap_or(engine);
     }
 
-    Redirect::to(format_url(q, engine.suggest_url))
+    let suggestions = match client.get(engine.suggest_url(q)).send().await {
+        Ok(resp) => match resp.text().await {
+            Ok(body) => engine.parse_suggestions(&body),
+            Err(_) => Vec::new(),
+        },
+        Err(_) => Vec::new(),
+    };
+
+    Json((q.to_string(), suggestions))
 }
 
 // server index.html + opensearch.xml so that we can be added to browsers
@@ -154,7 +117,53 @@ async fn index() -> Option<NamedFile> {
     NamedFile::open(Path::new("static/index.html")).await.ok()
 }
 
-#[launch]
-fn rocket() -> _ {
-    rocket::build().mount("/", routes![search, suggest, opensearch, index])
-}
\ No newline at end of file
+/// manually trigger a reload of the config file, so engine/ssid-rule changes take effect
+/// without a restart. Also done automatically on SIGHUP.
+#[post("/reload")]
+fn reload(config: &State<ConfigState>) -> &'static str {
+    match config.reload() {
+        Ok(()) => "reloaded",
+        Err(_) => "reload failed, keeping previous config",
+    }
+}
+
+#[rocket::main]
+#[allow(clippy::result_large_err)]
+async fn main() -> Result<(), rocket::Error> {
+    let config_state = ConfigState::load(&config_path()).expect("failed to load config file");
+
+    // built once at startup so engine requests reuse pooled connections instead of paying
+    // the reconnect cost on every /search or /suggest call; proxy/pool settings come from
+    // the same config file but (unlike engines/ssid rules) aren't hot-reloadable
+    let client = config::build_client(&config_state.get().http_client);
+
+    // built once at startup alongside the http client, for the same reason: rate limit
+    // settings aren't hot-reloadable, only the engines/ssid rules are
+    let rate_limiter = RateLimiter::new(config_state.get().rate_limit.clone());
+
+    // re-read the config file on SIGHUP so engine/ssid changes don't require a restart
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let reload_state = config_state.clone();
+        let mut hangup = signal(SignalKind::hangup()).expect("failed to register SIGHUP handler");
+        tokio::spawn(async move {
+            loop {
+                hangup.recv().await;
+                if let Err(e) = reload_state.reload() {
+                    eprintln!("SIGHUP config reload failed: {}", e);
+                }
+            }
+        });
+    }
+
+    rocket::build()
+        .manage(config_state)
+        .manage(client)
+        .manage(rate_limiter)
+        .mount("/", routes![search, suggest, opensearch, index, reload])
+        .launch()
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,162 @@
+//! opt-in per-ip sliding-window rate limiting, applied to `/search` and `/suggest` via the
+//! `RateLimited` request guard. Routes that don't take the guard (the static `/` and
+//! `/opensearch.xml`) are exempt, so the ui still loads while a client is being limited.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+
+use crate::config::RateLimitSettings;
+
+/// how many `allow()` calls between sweeps of stale per-ip entries. A plain request
+/// counter rather than a timer, so idle servers don't need a background task just to
+/// bound memory.
+const SWEEP_INTERVAL: u64 = 1000;
+
+struct Window {
+    start: Instant,
+    count: u32,
+}
+
+/// tracks request counts per client ip in a fixed-length sliding window. Constructed once
+/// at startup from the configured settings; `None` disables rate limiting entirely.
+pub struct RateLimiter {
+    settings: Option<RateLimitSettings>,
+    windows: Mutex<HashMap<IpAddr, Window>>,
+    requests_since_sweep: AtomicU64,
+}
+
+impl RateLimiter {
+    pub fn new(settings: Option<RateLimitSettings>) -> RateLimiter {
+        RateLimiter {
+            settings,
+            windows: Mutex::new(HashMap::new()),
+            requests_since_sweep: AtomicU64::new(0),
+        }
+    }
+
+    /// record a request from `ip`, returning whether it's still within the configured limit
+    fn allow(&self, ip: IpAddr) -> bool {
+        let settings = match &self.settings {
+            Some(settings) => settings,
+            None => return true,
+        };
+
+        let mut windows = self.windows.lock().unwrap();
+        let now = Instant::now();
+
+        // every client ip that's ever made a request would otherwise stay in the map
+        // forever; periodically drop entries whose window has already lapsed
+        if self.requests_since_sweep.fetch_add(1, Ordering::Relaxed) >= SWEEP_INTERVAL {
+            self.requests_since_sweep.store(0, Ordering::Relaxed);
+            windows.retain(|_, window| now.duration_since(window.start) < settings.window);
+        }
+
+        let window = windows.entry(ip).or_insert_with(|| Window { start: now, count: 0 });
+
+        if now.duration_since(window.start) >= settings.window {
+            window.start = now;
+            window.count = 0;
+        }
+
+        window.count += 1;
+        window.count <= settings.max_requests
+    }
+}
+
+/// request guard that rejects with 429 once the client ip has exceeded the configured
+/// rate limit. Attach it to a route's argument list (as an unused `_` binding) to apply
+/// the limit to that route.
+pub struct RateLimited;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RateLimited {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let limiter = match req.rocket().state::<RateLimiter>() {
+            Some(limiter) => limiter,
+            None => return Outcome::Success(RateLimited),
+        };
+
+        // no way to identify the client; fail open rather than blocking everyone behind
+        // a proxy that doesn't forward the real address
+        let ip = match req.client_ip() {
+            Some(ip) => ip,
+            None => return Outcome::Success(RateLimited),
+        };
+
+        if limiter.allow(ip) {
+            Outcome::Success(RateLimited)
+        } else {
+            Outcome::Error((Status::TooManyRequests, ()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod allow_tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    fn ip() -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))
+    }
+
+    #[test]
+    fn disabled_always_allows() {
+        let limiter = RateLimiter::new(None);
+        for _ in 0..100 {
+            assert!(limiter.allow(ip()));
+        }
+    }
+
+    #[test]
+    fn allows_up_to_max_requests_then_rejects() {
+        let limiter = RateLimiter::new(Some(RateLimitSettings {
+            window: Duration::from_secs(60),
+            max_requests: 3,
+        }));
+
+        assert!(limiter.allow(ip()));
+        assert!(limiter.allow(ip()));
+        assert!(limiter.allow(ip()));
+        assert!(!limiter.allow(ip()));
+    }
+
+    #[test]
+    fn resets_after_the_window_elapses() {
+        let limiter = RateLimiter::new(Some(RateLimitSettings {
+            window: Duration::from_millis(20),
+            max_requests: 1,
+        }));
+
+        assert!(limiter.allow(ip()));
+        assert!(!limiter.allow(ip()));
+
+        sleep(Duration::from_millis(30));
+
+        assert!(limiter.allow(ip()));
+    }
+
+    #[test]
+    fn tracks_ips_independently() {
+        let limiter = RateLimiter::new(Some(RateLimitSettings {
+            window: Duration::from_secs(60),
+            max_requests: 1,
+        }));
+
+        let other = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2));
+        assert!(limiter.allow(ip()));
+        assert!(!limiter.allow(ip()));
+        assert!(limiter.allow(other));
+    }
+}
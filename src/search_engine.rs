@@ -0,0 +1,501 @@
+//! search engine selection, bang parsing, and (optional) multi-engine result aggregation
+
+use std::collections::HashMap;
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Deserialize;
+use urlencoding::encode;
+
+use crate::config::Config;
+
+/// a single result scraped out of an engine's search result page, before merging with
+/// results from other engines
+pub struct ScrapedResult {
+    pub url: String,
+    pub title: String,
+    pub snippet: String,
+}
+
+/// a result shown on the aggregated results page, possibly sourced from more than one engine
+pub struct SearchResult {
+    pub url: String,
+    pub title: String,
+    pub snippet: String,
+    pub engines: Vec<String>,
+}
+
+/// shared handle to a configured search engine. Cheap to clone (an `Arc` underneath), since
+/// engines are looked up and passed around per-request.
+pub type EngineHandle = Arc<dyn Engine + Send + Sync>;
+
+/// a search provider: knows how to build its own search/suggest urls and how to parse its
+/// own suggestion (and, for some providers, result-page) responses. Each provider's
+/// suggest endpoint returns a different shape, so that parsing can't live in one generic
+/// place the way `format_url` can.
+pub trait Engine {
+    fn name(&self) -> &str;
+
+    /// fully formatted url to redirect a user to for this query
+    fn search_url(&self, q: &str) -> String;
+
+    /// fully formatted url to fetch autocomplete suggestions for this query
+    fn suggest_url(&self, q: &str) -> String;
+
+    /// parse this engine's raw suggest response into the common ordered list of strings
+    fn parse_suggestions(&self, body: &str) -> Vec<String>;
+
+    /// parse this engine's raw search result page into scraped results, used by aggregated
+    /// search. Only a handful of engines are scrapeable; the rest use the default (empty).
+    fn scrape_results(&self, _body: &str) -> Vec<ScrapedResult> {
+        Vec::new()
+    }
+
+    /// whether `scrape_results` does anything useful for this engine
+    fn can_scrape_results(&self) -> bool {
+        false
+    }
+}
+
+pub fn format_url(q: &str, format: &str) -> String {
+    format.replace("{searchTerms}", &encode(q).into_owned())
+}
+
+/// most OpenSearch-style suggest responses (google's firefox-client format, wikipedia's
+/// own opensearch api, ...) are a JSON array shaped `[query, [suggestion, ...], ...]`
+fn parse_opensearch_list(body: &str) -> Vec<String> {
+    let value: serde_json::Value = match serde_json::from_str(body) {
+        Ok(v) => v,
+        Err(_) => return Vec::new(),
+    };
+
+    value.get(1)
+        .and_then(|v| v.as_array())
+        .map(|arr| arr.iter().filter_map(|s| s.as_str().map(str::to_string)).collect())
+        .unwrap_or_default()
+}
+
+#[derive(Deserialize)]
+struct DdgSuggestion {
+    phrase: String,
+}
+
+/// duckduckgo's `type=list` autocomplete format is a JSON array of `{"phrase": "..."}`
+fn parse_ddg_phrase_list(body: &str) -> Vec<String> {
+    match serde_json::from_str::<Vec<DdgSuggestion>>(body) {
+        Ok(items) => items.into_iter().map(|i| i.phrase).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+pub struct GoogleEngine {
+    pub name: String,
+    pub search_url: String,
+    pub suggest_url: String,
+}
+
+impl Engine for GoogleEngine {
+    fn name(&self) -> &str { &self.name }
+    fn search_url(&self, q: &str) -> String { format_url(q, &self.search_url) }
+    fn suggest_url(&self, q: &str) -> String { format_url(q, &self.suggest_url) }
+    fn parse_suggestions(&self, body: &str) -> Vec<String> { parse_opensearch_list(body) }
+    fn scrape_results(&self, body: &str) -> Vec<ScrapedResult> { scrape_google(body) }
+    fn can_scrape_results(&self) -> bool { true }
+}
+
+pub struct DuckDuckGoEngine {
+    pub name: String,
+    pub search_url: String,
+    pub suggest_url: String,
+}
+
+impl Engine for DuckDuckGoEngine {
+    fn name(&self) -> &str { &self.name }
+    fn search_url(&self, q: &str) -> String { format_url(q, &self.search_url) }
+    fn suggest_url(&self, q: &str) -> String { format_url(q, &self.suggest_url) }
+    fn parse_suggestions(&self, body: &str) -> Vec<String> { parse_ddg_phrase_list(body) }
+    fn scrape_results(&self, body: &str) -> Vec<ScrapedResult> { scrape_duckduckgo(body) }
+    fn can_scrape_results(&self) -> bool { true }
+}
+
+pub struct WikipediaEngine {
+    pub name: String,
+    pub search_url: String,
+    pub suggest_url: String,
+}
+
+impl Engine for WikipediaEngine {
+    fn name(&self) -> &str { &self.name }
+    fn search_url(&self, q: &str) -> String { format_url(q, &self.search_url) }
+    fn suggest_url(&self, q: &str) -> String { format_url(q, &self.suggest_url) }
+    fn parse_suggestions(&self, body: &str) -> Vec<String> { parse_opensearch_list(body) }
+}
+
+/// any engine without a dedicated implementation. These typically reuse duckduckgo's
+/// `type=list` suggest endpoint as their `suggest_url` (the convention the hardcoded
+/// `DEFAULT_SUGGEST` const used to follow), so we parse suggestions the same way.
+pub struct GenericEngine {
+    pub name: String,
+    pub search_url: String,
+    pub suggest_url: String,
+}
+
+impl Engine for GenericEngine {
+    fn name(&self) -> &str { &self.name }
+    fn search_url(&self, q: &str) -> String { format_url(q, &self.search_url) }
+    fn suggest_url(&self, q: &str) -> String { format_url(q, &self.suggest_url) }
+    fn parse_suggestions(&self, body: &str) -> Vec<String> { parse_ddg_phrase_list(body) }
+}
+
+/// how long we'll wait on a single engine before giving up on it
+const AGGREGATE_TIMEOUT: Duration = Duration::from_secs(8);
+
+/// get the ssid we're connected to
+fn get_ssid() -> Option<String> {
+    Some(String::from_utf8(Command::new("iwgetid").arg("-r").output().ok()?.stdout).ok()?)
+}
+
+/// pick the default engine: the first ssid rule whose substring matches the connected
+/// ssid, or the configured global default otherwise
+pub fn base_engine(config: &Config) -> EngineHandle {
+    if let Some(ssid) = get_ssid() {
+        for (substring, engine_key) in &config.ssid_rules {
+            if ssid.contains(substring.as_str()) {
+                if let Some(engine) = config.engines.get(engine_key) {
+                    return engine.clone();
+                }
+            }
+        }
+    }
+
+    config.engines.get(&config.default_engine)
+        .expect("default_engine is validated against engines at config load time")
+        .clone()
+}
+
+/// return the prefered bang suggester (if configured)
+/// banged engines don't handle bangs in suggestions well, so use this instead
+pub fn get_bang_suggester(config: &Config) -> Option<EngineHandle> {
+    config.bang_suggester.as_ref().and_then(|key| config.engines.get(key)).cloned()
+}
+
+/// select a search engine for use
+pub fn get_engine<'q>(query: &'q str, config: &Config) -> (EngineHandle, &'q str) {
+    // check for bang command
+    if query.len() >= 1 && query.chars().nth(0) == Some('!') {
+        // get name before space
+        let mut index = 0;
+        for c in query.chars() {
+            index += 1;
+            if c.is_whitespace() {
+                break
+            }
+        }
+        // `index` is at least 1 here (the leading '!'); a bare "!" with nothing after it
+        // has no bang name to look up, so only slice once there's at least one more char
+        if index >= 2 {
+            let bang = &query[1..index-1];
+            // lookup bang
+            if let Some(engine) = config.engines.get(bang) {
+                if bang.len() + 2 <= query.len() {
+                    return (engine.clone(), &query[bang.len() + 2..]);
+                }
+            }
+        }
+    }
+
+    (base_engine(config), query)
+}
+
+/// fetch and merge autocomplete suggestions from several engines at once. If `q` is a bang
+/// query, only the bang-target engine and the bang suggester are queried (preferring the
+/// bang-target's own suggestions); otherwise every configured engine is queried.
+pub async fn aggregate_suggestions(q: &str, config: &Config, client: &reqwest::Client, max_results: usize) -> Vec<String> {
+    let (primary, new_query) = get_engine(q, config);
+    let is_bang = new_query != q;
+
+    let engines: Vec<EngineHandle> = if is_bang {
+        let mut engines = vec![primary.clone()];
+        if let Some(suggester) = get_bang_suggester(config) {
+            if suggester.name() != primary.name() {
+                engines.push(suggester);
+            }
+        }
+        engines
+    } else {
+        config.engines.values().cloned().collect()
+    };
+
+    let handles: Vec<_> = engines.into_iter().map(|engine| {
+        let client = client.clone();
+        let q = q.to_string();
+        tokio::spawn(async move {
+            match client.get(engine.suggest_url(&q)).send().await {
+                Ok(resp) => match resp.text().await {
+                    Ok(body) => engine.parse_suggestions(&body),
+                    Err(_) => Vec::new(),
+                },
+                Err(_) => Vec::new(),
+            }
+        })
+    }).collect();
+
+    let mut per_engine_lists = Vec::with_capacity(handles.len());
+    for handle in handles {
+        per_engine_lists.push(handle.await.unwrap_or_default());
+    }
+
+    interleave_dedup(per_engine_lists, max_results)
+}
+
+/// round-robin interleave multiple ordered suggestion lists, deduping case-insensitively
+/// and preserving first-seen order, up to `max_results`. Lists earlier in `lists` are
+/// preferred when there's a tie, so callers can rank a bang-target engine ahead of a
+/// generic fallback by listing it first.
+fn interleave_dedup(lists: Vec<Vec<String>>, max_results: usize) -> Vec<String> {
+    let mut seen = std::collections::HashSet::new();
+    let mut merged = Vec::new();
+    let longest = lists.iter().map(Vec::len).max().unwrap_or(0);
+
+    for i in 0..longest {
+        for list in &lists {
+            if merged.len() >= max_results {
+                return merged;
+            }
+            if let Some(suggestion) = list.get(i) {
+                if seen.insert(suggestion.to_lowercase()) {
+                    merged.push(suggestion.clone());
+                }
+            }
+        }
+    }
+
+    merged
+}
+
+/// strip the scheme, a leading `www.` and trailing slashes from a result url and lowercase
+/// it, so the same page from two engines dedups to one entry. Query strings are kept as-is.
+fn normalize_result_url(url: &str) -> String {
+    let without_scheme = url
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .trim_start_matches("www.");
+    without_scheme.trim_end_matches('/').to_lowercase()
+}
+
+/// scrape google's result page for organic results
+fn scrape_google(body: &str) -> Vec<ScrapedResult> {
+    use scraper::{Html, Selector};
+
+    let document = Html::parse_document(body);
+    let result_sel = Selector::parse("div.g").unwrap();
+    let title_sel = Selector::parse("h3").unwrap();
+    let link_sel = Selector::parse("a").unwrap();
+    let snippet_sel = Selector::parse("div.VwiC3b").unwrap();
+
+    document.select(&result_sel).filter_map(|el| {
+        let url = el.select(&link_sel).next()?.value().attr("href")?.to_string();
+        let title = el.select(&title_sel).next()?.text().collect::<String>();
+        let snippet = el.select(&snippet_sel).next().map(|s| s.text().collect::<String>()).unwrap_or_default();
+        Some(ScrapedResult { url, title, snippet })
+    }).collect()
+}
+
+/// scrape duckduckgo's html result page for organic results
+fn scrape_duckduckgo(body: &str) -> Vec<ScrapedResult> {
+    use scraper::{Html, Selector};
+
+    let document = Html::parse_document(body);
+    let result_sel = Selector::parse("div.result").unwrap();
+    let title_sel = Selector::parse("a.result__a").unwrap();
+    let snippet_sel = Selector::parse("a.result__snippet").unwrap();
+
+    document.select(&result_sel).filter_map(|el| {
+        let link = el.select(&title_sel).next()?;
+        let url = link.value().attr("href")?.to_string();
+        let title = link.text().collect::<String>();
+        let snippet = el.select(&snippet_sel).next().map(|s| s.text().collect::<String>()).unwrap_or_default();
+        Some(ScrapedResult { url, title, snippet })
+    }).collect()
+}
+
+/// fetch and scrape a single engine's results for `q`, used by aggregated search
+async fn fetch_engine_results(client: &reqwest::Client, engine: &EngineHandle, q: &str) -> Result<Vec<ScrapedResult>, String> {
+    if !engine.can_scrape_results() {
+        return Err(format!("no result scraper configured for {}", engine.name()));
+    }
+    let url = engine.search_url(q);
+
+    let fetch = async {
+        let resp = client.get(&url).send().await.map_err(|e| e.to_string())?;
+        resp.text().await.map_err(|e| e.to_string())
+    };
+
+    match tokio::time::timeout(AGGREGATE_TIMEOUT, fetch).await {
+        Ok(Ok(body)) => Ok(engine.scrape_results(&body)),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err("timed out".to_string()),
+    }
+}
+
+/// query every scrapeable engine concurrently and merge their results by url, along with
+/// the list of (engine name, error) for engines that failed or timed out
+pub async fn aggregate_search(q: &str, config: &Config, client: &reqwest::Client) -> (Vec<SearchResult>, Vec<(String, String)>) {
+    let handles: Vec<_> = config.engines.values()
+        .filter(|e| e.can_scrape_results())
+        .cloned()
+        .map(|engine| {
+            let client = client.clone();
+            let q = q.to_string();
+            tokio::spawn(async move {
+                let result = fetch_engine_results(&client, &engine, &q).await;
+                (engine.name().to_string(), result)
+            })
+        }).collect();
+
+    let mut merged: HashMap<String, SearchResult> = HashMap::new();
+    let mut errors = Vec::new();
+
+    for handle in handles {
+        match handle.await {
+            Ok((name, Ok(scraped))) => {
+                for r in scraped {
+                    let key = normalize_result_url(&r.url);
+                    merged.entry(key)
+                        .and_modify(|existing| {
+                            if !existing.engines.contains(&name) {
+                                existing.engines.push(name.clone());
+                            }
+                        })
+                        .or_insert(SearchResult {
+                            url: r.url,
+                            title: r.title,
+                            snippet: r.snippet,
+                            engines: vec![name.clone()],
+                        });
+                }
+            }
+            Ok((name, Err(e))) => errors.push((name, e)),
+            Err(e) => errors.push(("unknown".to_string(), e.to_string())),
+        }
+    }
+
+    (merged.into_values().collect(), errors)
+}
+
+#[cfg(test)]
+mod normalize_result_url_tests {
+    use super::normalize_result_url;
+
+    #[test]
+    fn strips_scheme_www_and_trailing_slash() {
+        assert_eq!(normalize_result_url("https://www.example.com/page/"), "example.com/page");
+    }
+
+    #[test]
+    fn same_page_from_different_engines_normalizes_equal() {
+        let google = normalize_result_url("https://www.example.com/page");
+        let ddg = normalize_result_url("http://example.com/page/");
+        assert_eq!(google, ddg);
+    }
+
+    #[test]
+    fn is_case_insensitive() {
+        assert_eq!(normalize_result_url("https://Example.COM/Page"), normalize_result_url("https://example.com/Page"));
+    }
+}
+
+#[cfg(test)]
+mod get_engine_tests {
+    use super::*;
+    use crate::config::HttpClientSettings;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    fn test_config() -> Config {
+        let mut engines: HashMap<String, EngineHandle> = HashMap::new();
+        engines.insert("ddg".to_string(), Arc::new(GenericEngine {
+            name: "ddg".to_string(),
+            search_url: "https://duckduckgo.com/?q={searchTerms}".to_string(),
+            suggest_url: "https://duckduckgo.com/ac/?q={searchTerms}&type=list".to_string(),
+        }));
+
+        Config {
+            engines,
+            default_engine: "ddg".to_string(),
+            bang_suggester: None,
+            ssid_rules: Vec::new(),
+            http_client: HttpClientSettings {
+                proxy: None,
+                pool_max_idle_per_host: 10,
+                tcp_keepalive: None,
+                timeout: Duration::from_secs(10),
+            },
+            rate_limit: None,
+            suggest_max_results: 10,
+        }
+    }
+
+    #[test]
+    fn bare_bang_with_no_name_does_not_panic() {
+        let config = test_config();
+        let (engine, query) = get_engine("!", &config);
+        // falls through to the default engine rather than looking up an empty bang name
+        assert_eq!(engine.name(), "ddg");
+        assert_eq!(query, "!");
+    }
+
+    #[test]
+    fn unknown_bang_falls_back_to_default_engine() {
+        let config = test_config();
+        let (engine, query) = get_engine("!nope rust", &config);
+        assert_eq!(engine.name(), "ddg");
+        assert_eq!(query, "!nope rust");
+    }
+
+    #[test]
+    fn known_bang_strips_prefix_and_selects_engine() {
+        let config = test_config();
+        let (engine, query) = get_engine("!ddg hello world", &config);
+        assert_eq!(engine.name(), "ddg");
+        assert_eq!(query, "hello world");
+    }
+}
+
+#[cfg(test)]
+mod interleave_dedup_tests {
+    use super::interleave_dedup;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn round_robins_across_lists() {
+        let lists = vec![strings(&["a1", "a2"]), strings(&["b1", "b2"])];
+        let merged = interleave_dedup(lists, 10);
+        assert_eq!(merged, strings(&["a1", "b1", "a2", "b2"]));
+    }
+
+    #[test]
+    fn dedups_case_insensitively_keeping_first_seen() {
+        let lists = vec![strings(&["Rust"]), strings(&["rust", "rust lang"])];
+        let merged = interleave_dedup(lists, 10);
+        assert_eq!(merged, strings(&["Rust", "rust lang"]));
+    }
+
+    #[test]
+    fn caps_at_max_results() {
+        let lists = vec![strings(&["a1", "a2", "a3"]), strings(&["b1", "b2", "b3"])];
+        let merged = interleave_dedup(lists, 3);
+        assert_eq!(merged, strings(&["a1", "b1", "a2"]));
+    }
+
+    #[test]
+    fn earlier_lists_are_preferred_on_tie() {
+        let lists = vec![strings(&["shared"]), strings(&["shared"])];
+        let merged = interleave_dedup(lists, 10);
+        assert_eq!(merged, strings(&["shared"]));
+    }
+}